@@ -4,7 +4,7 @@ use crate::proxy::http::{
     trace, upgrade, Version as HttpVersion,
 };
 use crate::transport::{
-    io::{self, BoxedIo, Peekable},
+    io::{self, BoxedIo},
     tls,
 };
 use crate::{
@@ -14,6 +14,7 @@ use crate::{
     Error,
 };
 use async_trait::async_trait;
+use bytes::BytesMut;
 use futures::TryFutureExt;
 use http;
 use hyper;
@@ -22,7 +23,10 @@ use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
-use tracing::{info_span, trace};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::time;
+use tracing::{info_span, trace, warn};
 use tracing_futures::Instrument;
 
 #[derive(Clone, Debug)]
@@ -33,19 +37,118 @@ pub struct Protocol {
 
 pub type Connection = (Protocol, BoxedIo);
 
+/// A connection-scoped fact that can be cloned into a request's extensions.
+///
+/// The blanket impl makes any `Clone` value insertable, so a hook can yield
+/// `Box::new(client_id)`, `Box::new(orig_dst)`, etc. Each value is inserted
+/// under its own concrete type, so downstream layers read it idiomatically with
+/// `req.extensions().get::<ClientId>()` rather than unwrapping an opaque
+/// container.
+pub trait ConnectionExtension: Send + Sync + 'static {
+    /// Inserts this fact into `extensions` under its concrete type.
+    fn insert(&self, extensions: &mut http::Extensions);
+}
+
+impl<T: Clone + Send + Sync + 'static> ConnectionExtension for T {
+    fn insert(&self, extensions: &mut http::Extensions) {
+        extensions.insert(self.clone());
+    }
+}
+
+/// A hook run once per accepted connection.
+///
+/// The returned facts are shared (via `Arc`) and inserted into every
+/// `http::Request` served on the connection, each under its own concrete type,
+/// so HTTP layers can read connection-scoped data—client mTLS identity,
+/// original destination, detected protocol, peer address—that is otherwise
+/// only available at service-construction time.
+pub type OnConnect =
+    Arc<dyn Fn(&Connection) -> Vec<Box<dyn ConnectionExtension>> + Send + Sync + 'static>;
+
+/// A policy consulted for HTTP/1 requests carrying `Expect: 100-continue`.
+///
+/// Returns `Ok(())` to let the request proceed—`hyper` emits the interim `100
+/// Continue` once the body is read—or `Err(status)` to reject the request with
+/// that final status before the body is accepted. Defaults to always-continue
+/// when unset, preserving the prior behavior.
+pub type Expect =
+    Arc<dyn Fn(&http::Request<Body>) -> Result<(), http::StatusCode> + Send + Sync + 'static>;
+
+/// Determines what happens when protocol detection does not complete within
+/// the configured deadline.
+#[derive(Clone, Copy, Debug)]
+pub enum DetectTimeoutPolicy {
+    /// Fail the connection with an `io::Error` of kind `TimedOut`.
+    Fail,
+    /// Treat the connection as non-HTTP and forward it as a raw TCP stream.
+    Forward,
+}
+
 #[derive(Clone, Debug)]
 pub struct ProtocolDetect {
     capacity: usize,
     skip_ports: Arc<IndexSet<u16>>,
+    detect_timeout: Duration,
+    timeout_policy: DetectTimeoutPolicy,
+    allow_h2c: bool,
 }
 
 impl ProtocolDetect {
     const PEEK_CAPACITY: usize = 8192;
+    const DETECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// The number of leading bytes required before the protocol can be
+    /// classified. This is the length of the HTTP/2 connection preface prefix
+    /// (`PRI * HTTP/2.0\r\n`)—the longest marker `HttpVersion::from_prefix`
+    /// inspects—so a prior-knowledge H2 client split across TCP segments is
+    /// still recognized rather than forwarded as raw TCP.
+    const MIN_DETECT_BYTES: usize = 16;
 
     pub fn new(skip_ports: Arc<IndexSet<u16>>) -> Self {
         ProtocolDetect {
             skip_ports,
             capacity: Self::PEEK_CAPACITY,
+            detect_timeout: Self::DETECT_TIMEOUT,
+            timeout_policy: DetectTimeoutPolicy::Fail,
+            allow_h2c: false,
+        }
+    }
+
+    /// Enables prior-knowledge HTTP/2 cleartext (h2c).
+    ///
+    /// A cleartext connection opening with the HTTP/2 connection preface is only
+    /// routed to the H2 serving path when this is enabled; otherwise such a
+    /// connection is forwarded as a raw TCP stream rather than served as
+    /// plaintext H2. Disabled by default, so h2c is opt-in per port.
+    pub fn with_h2c(mut self, allow_h2c: bool) -> Self {
+        self.allow_h2c = allow_h2c;
+        self
+    }
+
+    /// Sets the deadline for reading enough bytes to detect the protocol.
+    ///
+    /// A client that completes the TCP handshake but never sends data must not
+    /// hold the accepted connection open indefinitely.
+    pub fn with_detect_timeout(
+        mut self,
+        detect_timeout: Duration,
+        timeout_policy: DetectTimeoutPolicy,
+    ) -> Self {
+        self.detect_timeout = detect_timeout;
+        self.timeout_policy = timeout_policy;
+        self
+    }
+
+    /// Maps a protocol negotiated over TLS ALPN to an `HttpVersion`.
+    ///
+    /// Returns `None` for values we don't serve as HTTP (including the absence
+    /// of a negotiated protocol), in which case detection falls back to
+    /// prefix-sniffing.
+    fn alpn_http_version(alpn: Option<&[u8]>) -> Option<HttpVersion> {
+        match alpn? {
+            b"h2" => Some(HttpVersion::H2),
+            b"http/1.1" => Some(HttpVersion::Http1),
+            _ => None,
         }
     }
 }
@@ -58,7 +161,7 @@ impl detect::Detect<tls::accept::Meta> for ProtocolDetect {
     async fn detect(
         &self,
         tls: tls::accept::Meta,
-        io: BoxedIo,
+        mut io: BoxedIo,
     ) -> Result<(Self::Target, BoxedIo), Self::Error> {
         let port = tls.addrs.target_addr().port();
 
@@ -68,12 +171,88 @@ impl detect::Detect<tls::accept::Meta> for ProtocolDetect {
             return Ok::<_, Self::Error>((proto, io));
         }
 
-        // Otherwise, attempt to peek the client connection to determine the protocol.
-        // Currently, we only check for an HTTP prefix.
-        let peek = io.peek(self.capacity).await?;
-        let http = HttpVersion::from_prefix(peek.prefix().as_ref());
+        // If the connection was TLS-terminated and the peer negotiated an HTTP
+        // protocol via ALPN, trust that value and skip peeking entirely. This
+        // avoids buffering latency on the common meshed path and correctly
+        // handles server-speaks-first protocols, where the client may send no
+        // initial bytes and `peek` would otherwise block.
+        //
+        // `Meta.alpn` is populated by the TLS accept path from the protocol
+        // selected during the rustls handshake (see `tls::accept::Meta::tls`).
+        if let Some(http) = Self::alpn_http_version(tls.alpn.as_deref()) {
+            trace!(?http, "using ALPN-negotiated protocol");
+            let proto = Protocol { tls, http: Some(http) };
+            return Ok((proto, io));
+        }
+
+        // Otherwise, attempt to read the client's first bytes to determine the
+        // protocol. Currently, we only check for an HTTP prefix.
+        //
+        // The read is raced against `detect_timeout` so a client that completes
+        // the TCP handshake but sends nothing can't pin the connection slot
+        // forever. The bytes are buffered and prepended back onto the stream so
+        // the serving path observes the complete request.
+        // A single `read_buf` only returns the bytes in the first TCP segment,
+        // so a preface or request line split across segments would be
+        // misclassified. Read until we have enough bytes to classify the
+        // protocol or the peer stops sending, racing the whole loop against the
+        // detection deadline.
+        let mut prefix = BytesMut::with_capacity(self.capacity);
+        let read = {
+            let io = &mut io;
+            time::timeout(self.detect_timeout, async {
+                while prefix.len() < Self::MIN_DETECT_BYTES {
+                    if io.read_buf(&mut prefix).await? == 0 {
+                        // EOF: classify whatever we've received so far.
+                        break;
+                    }
+                }
+                Ok::<(), io::Error>(())
+            })
+            .await
+        };
+        match read {
+            Ok(res) => {
+                res?;
+            }
+            Err(_elapsed) => {
+                trace!(timeout = ?self.detect_timeout, "protocol detection timed out");
+                match self.timeout_policy {
+                    DetectTimeoutPolicy::Fail => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "protocol detection timed out",
+                        ));
+                    }
+                    DetectTimeoutPolicy::Forward => {
+                        warn!("protocol detection timed out; forwarding as TCP");
+                        let proto = Protocol { tls, http: None };
+                        let io = BoxedIo::new(io::PrefixedIo::new(prefix.freeze(), io));
+                        return Ok((proto, io));
+                    }
+                }
+            }
+        }
+
+        // Classifies the buffered prefix. A prior-knowledge h2c client's HTTP/2
+        // connection preface (`PRI * HTTP/2.0\r\n...`) is recognized here as
+        // `H2`; `MIN_DETECT_BYTES` above guarantees enough of the preface is
+        // buffered to classify it.
+        let http = HttpVersion::from_prefix(prefix.as_ref());
+
+        // Plaintext HTTP/2 (prior-knowledge h2c) is only served when h2c is
+        // enabled for this port; otherwise forward the connection as raw TCP
+        // rather than serving untrusted cleartext H2. TLS-negotiated H2 takes
+        // the ALPN short-circuit above and never reaches this path.
+        let http = match http {
+            Some(HttpVersion::H2) if !self.allow_h2c => {
+                trace!("plaintext HTTP/2 preface but h2c disabled; forwarding as TCP");
+                None
+            }
+            http => http,
+        };
         let proto = Protocol { tls, http };
-        Ok((proto, BoxedIo::new(peek)))
+        Ok((proto, BoxedIo::new(io::PrefixedIo::new(prefix.freeze(), io))))
     }
 }
 
@@ -104,6 +283,8 @@ where
     forward_tcp: F,
     make_http: H,
     drain: drain::Watch,
+    on_connect: Option<OnConnect>,
+    expect: Option<Expect>,
 }
 
 impl<F, H, B> Server<F, H, B>
@@ -123,8 +304,25 @@ where
             forward_tcp,
             make_http,
             drain,
+            on_connect: None,
+            expect: None,
         }
     }
+
+    /// Registers a hook run once per accepted connection whose returned facts
+    /// are inserted into every request served on that connection.
+    pub fn on_connect(mut self, on_connect: OnConnect) -> Self {
+        self.on_connect = Some(on_connect);
+        self
+    }
+
+    /// Registers a policy consulted for HTTP/1 requests carrying
+    /// `Expect: 100-continue`, allowing the request to be rejected before its
+    /// body is read. Defaults to always-continue when unset.
+    pub fn expect(mut self, expect: Expect) -> Self {
+        self.expect = Some(expect);
+        self
+    }
 }
 
 impl<F, H, B> Service<Connection> for Server<F, H, B>
@@ -132,7 +330,7 @@ where
     F: Accept<(tls::accept::Meta, BoxedIo)> + Clone + Send + 'static,
     F::Future: Send + 'static,
     F::ConnectionFuture: Send + 'static,
-    H: NewService<tls::accept::Meta> + Send + 'static,
+    H: NewService<tls::accept::Meta> + Clone + Send + 'static,
     H::Service: Service<http::Request<Body>, Response = http::Response<B>, Error = Error>
         + Unpin
         + Send
@@ -157,7 +355,11 @@ where
     /// what protocol the connection is speaking. From there, the connection
     /// will be mapped into respective services, and spawned into an
     /// executor.
-    fn call(&mut self, (proto, io): Connection) -> Self::Future {
+    fn call(&mut self, conn: Connection) -> Self::Future {
+        // Run the per-connection hook (if any) before consuming the connection,
+        // sharing its facts into every request served below.
+        let facts = self.on_connect.as_ref().map(|f| Arc::new(f(&conn)));
+        let (proto, io) = conn;
         let http_version = match proto.http {
             Some(http) => http,
             None => {
@@ -183,16 +385,20 @@ where
             }
         };
 
-        let http_svc = self.make_http.new_service(proto.tls);
+        let http_svc = InjectExtensions::new(self.make_http.new_service(proto.tls), facts);
 
         let mut builder = self.http.clone();
         let drain = self.drain.clone();
+        let expect = self.expect.clone();
         Box::pin(async move {
             let rsp: Self::Response = Box::pin(async move {
                 match http_version {
                     HttpVersion::Http1 => {
+                        // Consult the expectation policy for `Expect: 100-continue`
+                        // requests before the body is read.
+                        let svc = expectation::Expect::new(http_svc, expect);
                         // Enable support for HTTP upgrades (CONNECT and websockets).
-                        let svc = upgrade::Service::new(http_svc, drain.clone());
+                        let svc = upgrade::Service::new(svc, drain.clone());
                         let conn = builder
                             .http1_only(true)
                             .serve_connection(io, HyperServerSvc::new(svc))
@@ -235,6 +441,143 @@ where
             forward_tcp: self.forward_tcp.clone(),
             make_http: self.make_http.clone(),
             drain: self.drain.clone(),
+            on_connect: self.on_connect.clone(),
+            expect: self.expect.clone(),
+        }
+    }
+}
+
+/// Inserts a connection's facts into every request served on it.
+///
+/// Each fact from the hook is inserted under its own concrete type (not a
+/// nested container), so downstream layers read it with
+/// `req.extensions().get::<T>()`. The fact list is shared via `Arc`, so the
+/// per-request cost on a pooled HTTP/2 connection is one clone of each value,
+/// not of the whole connection state.
+struct InjectExtensions<S> {
+    inner: S,
+    facts: Option<Arc<Vec<Box<dyn ConnectionExtension>>>>,
+}
+
+impl<S> InjectExtensions<S> {
+    fn new(inner: S, facts: Option<Arc<Vec<Box<dyn ConnectionExtension>>>>) -> Self {
+        Self { inner, facts }
+    }
+}
+
+impl<S, B> Service<http::Request<Body>> for InjectExtensions<S>
+where
+    S: Service<http::Request<Body>, Response = http::Response<B>, Error = Error>,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<Body>) -> Self::Future {
+        if let Some(facts) = self.facts.as_ref() {
+            let extensions = req.extensions_mut();
+            for fact in facts.iter() {
+                fact.insert(extensions);
+            }
+        }
+        self.inner.call(req)
+    }
+}
+
+/// HTTP/1 `Expect: 100-continue` handling.
+///
+/// Wraps an HTTP/1 service so that a request advertising `Expect: 100-continue`
+/// is first run through the configured [`Expect`] policy. On `Ok` the request
+/// proceeds and `hyper` emits the interim `100 Continue` when the body is read;
+/// on `Err` the request is answered with a final status before its body is
+/// accepted. With no policy configured every request continues, matching the
+/// prior behavior.
+mod expectation {
+    use super::*;
+    use http::header::EXPECT;
+
+    /// Wraps an HTTP/1 service, enforcing the `Expect: 100-continue` policy.
+    pub(super) struct Expect<S> {
+        inner: S,
+        policy: Option<super::Expect>,
+    }
+
+    impl<S> Expect<S> {
+        pub(super) fn new(inner: S, policy: Option<super::Expect>) -> Self {
+            Self { inner, policy }
+        }
+    }
+
+    /// Returns `true` if the request carries `Expect: 100-continue`.
+    pub(super) fn expects_continue(headers: &http::HeaderMap) -> bool {
+        headers
+            .get(EXPECT)
+            .map(|v| v.as_bytes().eq_ignore_ascii_case(b"100-continue"))
+            .unwrap_or(false)
+    }
+
+    impl<S, B> Service<http::Request<Body>> for Expect<S>
+    where
+        S: Service<http::Request<Body>, Response = http::Response<B>, Error = Error>,
+        S::Future: Send + 'static,
+        B: Default + 'static,
+    {
+        type Response = http::Response<B>;
+        type Error = Error;
+        type Future =
+            Pin<Box<dyn Future<Output = Result<http::Response<B>, Error>> + Send + 'static>>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.inner.poll_ready(cx)
+        }
+
+        fn call(&mut self, req: http::Request<Body>) -> Self::Future {
+            if let Some(policy) = self.policy.as_ref() {
+                if expects_continue(req.headers()) {
+                    if let Err(status) = policy(&req) {
+                        trace!(?status, "rejecting 100-continue request");
+                        // Respond with a final status before the body is read;
+                        // hyper sends this instead of an interim `100 Continue`.
+                        let mut rsp = http::Response::new(B::default());
+                        *rsp.status_mut() = status;
+                        return Box::pin(async move { Ok(rsp) });
+                    }
+                }
+            }
+            Box::pin(self.inner.call(req))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::expects_continue;
+        use http::header::EXPECT;
+        use http::HeaderMap;
+
+        #[test]
+        fn detects_expectation() {
+            let mut h = HeaderMap::new();
+            h.insert(EXPECT, "100-continue".parse().unwrap());
+            assert!(expects_continue(&h));
+        }
+
+        #[test]
+        fn case_insensitive() {
+            let mut h = HeaderMap::new();
+            h.insert(EXPECT, "100-Continue".parse().unwrap());
+            assert!(expects_continue(&h));
+        }
+
+        #[test]
+        fn absent_or_other() {
+            assert!(!expects_continue(&HeaderMap::new()));
+            let mut h = HeaderMap::new();
+            h.insert(EXPECT, "200-ok".parse().unwrap());
+            assert!(!expects_continue(&h));
         }
     }
 }
@@ -1,7 +1,9 @@
 use crate::error::Failed;
 use crate::InFlight;
+use futures::task::AtomicTask;
 use futures::{Async, Future, Poll, Stream};
 use linkerd2_error::{Error, Never};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
@@ -13,6 +15,15 @@ pub struct Dispatch<S, Req, Rsp> {
     rx: mpsc::Receiver<InFlight<Req, Rsp>>,
     probe_timeout: Duration,
     probe: Option<Delay>,
+    /// The maximum number of response futures that may run concurrently.
+    max_in_flight: usize,
+    /// The number of response futures currently in flight. Shared with the
+    /// owner as a metrics gauge; decremented by each spawned future as it
+    /// completes.
+    in_flight: Arc<AtomicUsize>,
+    /// Notified by a completing response future so the dispatch loop wakes once
+    /// the in-flight ceiling is no longer saturated.
+    capacity: Arc<AtomicTask>,
 }
 
 impl<S, Req> Dispatch<S, Req, S::Response>
@@ -22,16 +33,28 @@ where
     S::Response: Send + 'static,
     S::Future: Send + 'static,
 {
+    /// Creates a dispatcher bounded to `max_in_flight` concurrent response
+    /// futures, reporting the current in-flight count through the shared
+    /// `in_flight` gauge (the owner registers this `Arc` with its metrics).
+    ///
+    /// Panics if `max_in_flight` is zero: a zero ceiling would park the
+    /// dispatcher forever, since `in_flight` can never drop below it.
     pub(crate) fn new(
         inner: S,
         rx: mpsc::Receiver<InFlight<Req, S::Response>>,
         probe_timeout: Duration,
+        max_in_flight: usize,
+        in_flight: Arc<AtomicUsize>,
     ) -> Self {
+        assert!(max_in_flight >= 1, "max_in_flight must be at least 1");
         Self {
             inner,
             rx,
             probe_timeout,
             probe: None,
+            max_in_flight,
+            in_flight,
+            capacity: Arc::new(AtomicTask::new()),
         }
     }
 }
@@ -48,6 +71,18 @@ where
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         loop {
+            // Don't accept more work while the in-flight ceiling is saturated:
+            // stop pulling from `rx` and stop re-probing the inner service,
+            // parking until a spawned response future completes and frees a
+            // slot. The task is re-registered and the count re-checked to avoid
+            // missing a wakeup that races with registration.
+            if self.in_flight.load(Ordering::Acquire) >= self.max_in_flight {
+                self.capacity.register();
+                if self.in_flight.load(Ordering::Acquire) >= self.max_in_flight {
+                    return Ok(Async::NotReady);
+                }
+            }
+
             let needs_ready = match self.probe.as_mut() {
                 None => true,
                 // If the probe was set, then the inner service is already ready and
@@ -89,8 +124,16 @@ where
 
                 // If a request was ready, spawn its response future
                 Ok(Async::Ready(Some(InFlight { request, tx }))) => {
+                    // Reserve a slot before spawning; the future releases it on
+                    // completion and wakes the dispatch loop so a parked
+                    // dispatcher can resume pulling requests.
+                    self.in_flight.fetch_add(1, Ordering::AcqRel);
+                    let in_flight = self.in_flight.clone();
+                    let capacity = self.capacity.clone();
                     tokio::spawn(self.inner.call(request).then(move |res| {
                         let _ = tx.send(res.map_err(Into::into));
+                        in_flight.fetch_sub(1, Ordering::AcqRel);
+                        capacity.notify();
                         Ok(())
                     }));
                 }
@@ -111,3 +154,49 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+
+    /// A service that is always ready and completes immediately.
+    struct Ready;
+
+    impl tower::Service<()> for Ready {
+        type Response = ();
+        type Error = Error;
+        type Future = future::FutureResult<(), Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "max_in_flight must be at least 1")]
+    fn rejects_zero_max_in_flight() {
+        let (_tx, rx) = mpsc::channel::<InFlight<(), ()>>(1);
+        let _ = Dispatch::new(
+            Ready,
+            rx,
+            Duration::from_secs(1),
+            0,
+            Arc::new(AtomicUsize::new(0)),
+        );
+    }
+
+    #[test]
+    fn accepts_shared_gauge() {
+        let (_tx, rx) = mpsc::channel::<InFlight<(), ()>>(1);
+        let gauge = Arc::new(AtomicUsize::new(0));
+        let dispatch = Dispatch::new(Ready, rx, Duration::from_secs(1), 1, gauge.clone());
+        // The dispatcher reports through the same gauge the owner registered
+        // with its metrics.
+        assert!(Arc::ptr_eq(&dispatch.in_flight, &gauge));
+    }
+}
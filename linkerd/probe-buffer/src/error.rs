@@ -0,0 +1,38 @@
+use linkerd2_error::Error;
+use std::sync::Arc;
+use std::{error, fmt};
+
+/// The inner service failed; shared with every request still queued for the
+/// dispatch task when the failure was observed.
+#[derive(Clone, Debug)]
+pub(crate) struct Failed(pub(crate) Arc<Error>);
+
+/// The dispatch task terminated, so the buffer can no longer serve requests.
+#[derive(Debug)]
+pub struct Closed(());
+
+impl Closed {
+    pub(crate) fn new() -> Self {
+        Closed(())
+    }
+}
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl error::Error for Failed {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&**self.0)
+    }
+}
+
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "buffer closed")
+    }
+}
+
+impl error::Error for Closed {}
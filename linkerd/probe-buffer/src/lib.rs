@@ -0,0 +1,106 @@
+//! A bounded request buffer that drives its inner service on a dedicated task.
+//!
+//! Requests are handed to a [`Dispatch`] task over a channel; the task calls the
+//! inner service and spawns each response future, bounded by a configurable
+//! in-flight ceiling. The in-flight count is exposed as a shared gauge so the
+//! owner can register it with the proxy's metrics.
+
+#![deny(warnings, rust_2018_idioms)]
+
+mod dispatch;
+mod error;
+
+pub use self::error::Closed;
+
+use self::dispatch::Dispatch;
+use futures::{Async, Future, Poll};
+use linkerd2_error::Error;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+/// A request in flight between the [`Buffer`] and its [`Dispatch`] task.
+pub(crate) struct InFlight<Req, Rsp> {
+    pub(crate) request: Req,
+    pub(crate) tx: oneshot::Sender<Result<Rsp, Error>>,
+}
+
+/// A `Service` that enqueues requests onto a bounded [`Dispatch`] task.
+pub struct Buffer<Req, Rsp> {
+    tx: mpsc::Sender<InFlight<Req, Rsp>>,
+}
+
+/// The future returned by [`Buffer::call`], resolving once the dispatch task
+/// has driven the inner service to a response.
+pub struct ResponseFuture<Rsp> {
+    rx: oneshot::Receiver<Result<Rsp, Error>>,
+}
+
+/// Builds a buffer for `inner`, spawning its dispatch task.
+///
+/// `capacity` bounds the request channel and `max_in_flight` bounds the number
+/// of response futures running concurrently. Returns the [`Buffer`] service and
+/// the in-flight gauge, which the caller registers with its metrics.
+pub fn spawn<S, Req>(
+    inner: S,
+    capacity: usize,
+    max_in_flight: usize,
+    probe_timeout: Duration,
+) -> (Buffer<Req, S::Response>, Arc<AtomicUsize>)
+where
+    Req: Send + 'static,
+    S: tower::Service<Req> + Send + 'static,
+    S::Error: Into<Error>,
+    S::Response: Send + 'static,
+    S::Future: Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(capacity);
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let dispatch = Dispatch::new(inner, rx, probe_timeout, max_in_flight, in_flight.clone());
+    tokio::spawn(dispatch.map_err(|n| match n {}));
+    (Buffer { tx }, in_flight)
+}
+
+impl<Req, Rsp> tower::Service<Req> for Buffer<Req, Rsp> {
+    type Response = Rsp;
+    type Error = Error;
+    type Future = ResponseFuture<Rsp>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.tx.poll_ready().map_err(|_| Closed::new().into())
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        let (tx, rx) = oneshot::channel();
+        // `poll_ready` reserved a slot, so the send should not fail; if the
+        // dispatch task has gone away the response future reports `Closed`.
+        if self.tx.try_send(InFlight { request, tx }).is_err() {
+            tracing::trace!("buffer dispatch task dropped");
+        }
+        ResponseFuture { rx }
+    }
+}
+
+impl<Req, Rsp> Clone for Buffer<Req, Rsp> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<Rsp> Future for ResponseFuture<Rsp> {
+    type Item = Rsp;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.rx.poll() {
+            Ok(Async::Ready(Ok(rsp))) => Ok(Async::Ready(rsp)),
+            Ok(Async::Ready(Err(error))) => Err(error),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // The dispatch task dropped the sender without responding.
+            Err(_) => Err(Closed::new().into()),
+        }
+    }
+}
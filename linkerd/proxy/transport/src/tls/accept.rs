@@ -0,0 +1,55 @@
+use super::ReasonForNoPeerName;
+use crate::listen::Addrs;
+use linkerd2_conditional::Conditional;
+use linkerd2_identity as identity;
+use rustls::Session;
+use tokio_rustls::server::TlsStream;
+
+/// The peer's identity, when the connection was mutually authenticated.
+pub type PeerIdentity = Conditional<identity::Name, ReasonForNoPeerName>;
+
+/// Describes an accepted connection as it enters the proxy stack.
+///
+/// Produced by the TLS accept path and handed to protocol detection, it carries
+/// the connection's addresses, the peer's verified identity, and the
+/// application protocol negotiated via ALPN during the TLS handshake.
+#[derive(Clone, Debug)]
+pub struct Meta {
+    /// The source and original destination addresses of the connection.
+    pub addrs: Addrs,
+
+    /// The peer's verified identity, when the connection was mutually
+    /// authenticated.
+    pub peer_identity: PeerIdentity,
+
+    /// The application protocol selected via ALPN during the TLS handshake
+    /// (e.g. `b"h2"`, `b"http/1.1"`). `None` for plaintext connections or when
+    /// no protocol was negotiated, in which case protocol detection falls back
+    /// to prefix-sniffing.
+    pub alpn: Option<Vec<u8>>,
+}
+
+impl Meta {
+    /// Builds metadata for a TLS-terminated connection, capturing the ALPN
+    /// protocol selected during the handshake.
+    pub(crate) fn tls<I>(addrs: Addrs, peer_identity: PeerIdentity, tls: &TlsStream<I>) -> Self {
+        // `get_ref().1` is the server-side `Session`; its negotiated protocol
+        // is only available once the handshake has completed.
+        let alpn = tls.get_ref().1.get_alpn_protocol().map(|p| p.to_vec());
+        Self {
+            addrs,
+            peer_identity,
+            alpn,
+        }
+    }
+
+    /// Builds metadata for a plaintext (non-TLS) connection, which has no
+    /// negotiated protocol.
+    pub(crate) fn plaintext(addrs: Addrs, peer_identity: PeerIdentity) -> Self {
+        Self {
+            addrs,
+            peer_identity,
+            alpn: None,
+        }
+    }
+}